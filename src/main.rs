@@ -1,9 +1,16 @@
-use clap::Parser;
-use std::collections::HashMap;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{self, Write};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Plain,
+    Json,
+}
 
 #[derive(Parser)]
 #[command(name = "iostat", about = "Report I/O statistics")]
@@ -32,6 +39,22 @@ struct Args {
     #[arg(short = 'y', long)]
     omit_first: bool,
 
+    /// Display a single aggregated "total" row summed across all devices
+    #[arg(short = 'g', long = "total")]
+    total: bool,
+
+    /// Output format
+    #[arg(short = 'o', long = "output", value_enum, default_value = "plain")]
+    output: OutputFormat,
+
+    /// Print a device model column sourced from /sys/block
+    #[arg(short = 'M', long = "model")]
+    show_model: bool,
+
+    /// Report partitions too, indented under their parent disk
+    #[arg(short = 'p', long = "partitions")]
+    partitions: bool,
+
     /// Interval in seconds
     #[arg(default_value = "1")]
     interval: f64,
@@ -39,6 +62,9 @@ struct Args {
     /// Number of reports (0 = infinite)
     #[arg(default_value = "0")]
     count: u32,
+
+    /// Restrict the report to these devices (and their partitions)
+    devices: Vec<String>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -85,6 +111,28 @@ impl CpuStats {
             (self.irq + self.softirq) as f64 / total * 100.0,
         )
     }
+
+    fn report(&self) -> CpuReport {
+        let (user, system, iowait, steal, idle, irq) = self.percentages();
+        CpuReport {
+            user,
+            system,
+            iowait,
+            steal,
+            idle,
+            irq,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct CpuReport {
+    user: f64,
+    system: f64,
+    iowait: f64,
+    steal: f64,
+    idle: f64,
+    irq: f64,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -100,6 +148,14 @@ struct DiskStats {
     io_in_progress: u64,
     io_time_ms: u64,
     weighted_io_time_ms: u64,
+    // Kernels >= 4.18
+    discards_completed: u64,
+    discards_merged: u64,
+    sectors_discarded: u64,
+    discard_time_ms: u64,
+    // Kernels >= 5.5
+    flushes_completed: u64,
+    flush_time_ms: u64,
 }
 
 impl DiskStats {
@@ -116,8 +172,135 @@ impl DiskStats {
             io_in_progress: self.io_in_progress,
             io_time_ms: self.io_time_ms.saturating_sub(prev.io_time_ms),
             weighted_io_time_ms: self.weighted_io_time_ms.saturating_sub(prev.weighted_io_time_ms),
+            discards_completed: self.discards_completed.saturating_sub(prev.discards_completed),
+            discards_merged: self.discards_merged.saturating_sub(prev.discards_merged),
+            sectors_discarded: self.sectors_discarded.saturating_sub(prev.sectors_discarded),
+            discard_time_ms: self.discard_time_ms.saturating_sub(prev.discard_time_ms),
+            flushes_completed: self.flushes_completed.saturating_sub(prev.flushes_completed),
+            flush_time_ms: self.flush_time_ms.saturating_sub(prev.flush_time_ms),
+        }
+    }
+
+    /// Sums two counter snapshots, e.g. to fold several physical devices into
+    /// one synthetic row (a `--total` summary, or a device-mapper target's
+    /// component devices).
+    fn merged(&self, other: &DiskStats) -> DiskStats {
+        DiskStats {
+            reads_completed: self.reads_completed + other.reads_completed,
+            reads_merged: self.reads_merged + other.reads_merged,
+            sectors_read: self.sectors_read + other.sectors_read,
+            read_time_ms: self.read_time_ms + other.read_time_ms,
+            writes_completed: self.writes_completed + other.writes_completed,
+            writes_merged: self.writes_merged + other.writes_merged,
+            sectors_written: self.sectors_written + other.sectors_written,
+            write_time_ms: self.write_time_ms + other.write_time_ms,
+            io_in_progress: self.io_in_progress + other.io_in_progress,
+            io_time_ms: self.io_time_ms + other.io_time_ms,
+            weighted_io_time_ms: self.weighted_io_time_ms + other.weighted_io_time_ms,
+            discards_completed: self.discards_completed + other.discards_completed,
+            discards_merged: self.discards_merged + other.discards_merged,
+            sectors_discarded: self.sectors_discarded + other.sectors_discarded,
+            discard_time_ms: self.discard_time_ms + other.discard_time_ms,
+            flushes_completed: self.flushes_completed + other.flushes_completed,
+            flush_time_ms: self.flush_time_ms + other.flush_time_ms,
         }
     }
+
+    fn report(&self, name: &str, interval_secs: f64, unit_divisor: f64, sys_info: &SysBlockInfo) -> DeviceReport {
+        let r_s = self.reads_completed as f64 / interval_secs;
+        let w_s = self.writes_completed as f64 / interval_secs;
+
+        let rkb_s = (self.sectors_read as f64 * 512.0) / 1024.0 / interval_secs / unit_divisor;
+        let wkb_s = (self.sectors_written as f64 * 512.0) / 1024.0 / interval_secs / unit_divisor;
+
+        let rrqm_s = self.reads_merged as f64 / interval_secs;
+        let wrqm_s = self.writes_merged as f64 / interval_secs;
+
+        let total_ios = self.reads_completed + self.writes_completed;
+
+        let r_await = if self.reads_completed > 0 {
+            self.read_time_ms as f64 / self.reads_completed as f64
+        } else {
+            0.0
+        };
+        let w_await = if self.writes_completed > 0 {
+            self.write_time_ms as f64 / self.writes_completed as f64
+        } else {
+            0.0
+        };
+
+        let svctm = if total_ios > 0 {
+            self.io_time_ms as f64 / total_ios as f64
+        } else {
+            0.0
+        };
+
+        let aqu_sz = self.weighted_io_time_ms as f64 / 1000.0 / interval_secs;
+        let areq_sz = if total_ios > 0 {
+            (self.sectors_read + self.sectors_written) as f64 * 512.0 / 1024.0 / total_ios as f64
+        } else {
+            0.0
+        };
+
+        let util = ((self.io_time_ms as f64 / (interval_secs * 1000.0)) * 100.0).min(100.0);
+
+        let d_s = self.discards_completed as f64 / interval_secs;
+        let dkb_s = (self.sectors_discarded as f64 * 512.0) / 1024.0 / interval_secs / unit_divisor;
+        let drqm_s = self.discards_merged as f64 / interval_secs;
+        let f_s = self.flushes_completed as f64 / interval_secs;
+        let f_await = if self.flushes_completed > 0 {
+            self.flush_time_ms as f64 / self.flushes_completed as f64
+        } else {
+            0.0
+        };
+
+        DeviceReport {
+            device: name.to_string(),
+            model: sys_info.model.clone(),
+            is_ssd: sys_info.is_ssd(),
+            r_s,
+            w_s,
+            rkb_s,
+            wkb_s,
+            rrqm_s,
+            wrqm_s,
+            r_await,
+            w_await,
+            svctm,
+            aqu_sz,
+            areq_sz,
+            util,
+            d_s,
+            dkb_s,
+            drqm_s,
+            f_s,
+            f_await,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DeviceReport {
+    device: String,
+    model: Option<String>,
+    is_ssd: Option<bool>,
+    r_s: f64,
+    w_s: f64,
+    rkb_s: f64,
+    wkb_s: f64,
+    rrqm_s: f64,
+    wrqm_s: f64,
+    r_await: f64,
+    w_await: f64,
+    svctm: f64,
+    aqu_sz: f64,
+    areq_sz: f64,
+    util: f64,
+    d_s: f64,
+    dkb_s: f64,
+    drqm_s: f64,
+    f_s: f64,
+    f_await: f64,
 }
 
 fn read_cpu_stats() -> io::Result<CpuStats> {
@@ -142,8 +325,19 @@ fn read_cpu_stats() -> io::Result<CpuStats> {
     Ok(CpuStats::default())
 }
 
-fn read_disk_stats() -> io::Result<HashMap<String, DiskStats>> {
+/// Reads every line of `/proc/diskstats` into its raw counters, partitions
+/// included. Filtering by `-p`/device name happens afterwards, once
+/// device-mapper targets have had a chance to pull in their slaves' stats.
+fn read_all_disk_stats() -> io::Result<HashMap<String, DiskStats>> {
     let content = fs::read_to_string("/proc/diskstats")?;
+    Ok(parse_disk_stats(&content))
+}
+
+/// Parses the contents of `/proc/diskstats` into per-device counters.
+/// Discard fields appear on kernels >= 4.18 (4 fields), flush fields on
+/// kernels >= 5.5 (2 more fields); both default to 0 when the line is
+/// shorter, so older kernels still parse.
+fn parse_disk_stats(content: &str) -> HashMap<String, DiskStats> {
     let mut stats = HashMap::new();
 
     for line in content.lines() {
@@ -151,11 +345,7 @@ fn read_disk_stats() -> io::Result<HashMap<String, DiskStats>> {
         if parts.len() >= 14 {
             let name = parts[2].to_string();
 
-            // Skip partitions (devices ending in digit after letters, like nvme0n1p1)
-            // Keep whole disks: sda, nvme0n1, etc.
-            if is_partition(&name) {
-                continue;
-            }
+            let get = |i: usize| parts.get(i).and_then(|s| s.parse().ok()).unwrap_or(0u64);
 
             stats.insert(
                 name,
@@ -171,34 +361,207 @@ fn read_disk_stats() -> io::Result<HashMap<String, DiskStats>> {
                     io_in_progress: parts[11].parse().unwrap_or(0),
                     io_time_ms: parts[12].parse().unwrap_or(0),
                     weighted_io_time_ms: parts[13].parse().unwrap_or(0),
+                    discards_completed: get(14),
+                    discards_merged: get(15),
+                    sectors_discarded: get(16),
+                    discard_time_ms: get(17),
+                    flushes_completed: get(18),
+                    flush_time_ms: get(19),
                 },
             );
         }
     }
 
-    Ok(stats)
+    stats
 }
 
-fn is_partition(name: &str) -> bool {
+/// Replaces each `dm-*` entry with its friendly LVM/crypt name (from
+/// `/sys/block/dm-*/dm/name`) and its stats with the sum of its component
+/// devices (from `/sys/block/dm-*/slaves/`), falling back to the dm
+/// device's own counters when the slaves listing is missing or empty.
+/// Also returns the set of physical device names that were folded into a
+/// dm target, so callers that sum across the whole map (`--total`) can
+/// exclude them and avoid counting the same I/O twice.
+fn resolve_device_mapper(raw: HashMap<String, DiskStats>) -> (HashMap<String, DiskStats>, HashSet<String>) {
+    resolve_device_mapper_at(SYS_BLOCK_PATH, raw)
+}
+
+/// Same as `resolve_device_mapper`, but reading dm metadata from
+/// `sys_block_path` instead of the real `/sys/block` so tests can point it
+/// at a fixture directory.
+fn resolve_device_mapper_at(sys_block_path: &str, raw: HashMap<String, DiskStats>) -> (HashMap<String, DiskStats>, HashSet<String>) {
+    let mut resolved = raw.clone();
+    let mut slaves = HashSet::new();
+
+    for name in raw.keys().filter(|name| name.starts_with("dm-")) {
+        let base = format!("{}/{}", sys_block_path, name);
+
+        let friendly_name = fs::read_to_string(format!("{}/dm/name", base))
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| name.clone());
+
+        let slave_names: Vec<String> = fs::read_dir(format!("{}/slaves", base))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|slave| raw.contains_key(slave))
+            .collect();
+
+        let aggregated = if slave_names.is_empty() {
+            raw.get(name).cloned().unwrap_or_default()
+        } else {
+            slave_names
+                .iter()
+                .filter_map(|slave| raw.get(slave))
+                .fold(DiskStats::default(), |acc, stats| acc.merged(stats))
+        };
+        slaves.extend(slave_names);
+
+        resolved.remove(name);
+        resolved.insert(friendly_name, aggregated);
+    }
+
+    (resolved, slaves)
+}
+
+fn read_disk_stats(devices: &[String], partitions: bool) -> io::Result<(HashMap<String, DiskStats>, HashSet<String>)> {
+    let raw = read_all_disk_stats()?;
+    let (resolved, dm_slaves) = resolve_device_mapper(raw);
+
+    let stats = resolved
+        .into_iter()
+        .filter(|(name, _)| partitions || !is_partition(name))
+        .filter(|(name, _)| matches_device_filter(name, devices))
+        .collect();
+
+    Ok((stats, dm_slaves))
+}
+
+/// Whether `name` should be included given the device names listed on the
+/// command line (empty means "no filter"); matches the device itself or any of its
+/// partitions (e.g. `sda` also allows `sda1`, but not the unrelated disk
+/// `sdaa` or its partitions).
+fn matches_device_filter(name: &str, devices: &[String]) -> bool {
+    devices.is_empty()
+        || devices
+            .iter()
+            .any(|d| name == d || partition_parent(name).as_deref() == Some(d.as_str()))
+}
+
+/// Sums stats across all non-loopback devices into a single synthetic
+/// "total" row. `dm_slaves` excludes the physical devices already folded
+/// into a device-mapper target's row, and partitions are excluded
+/// outright, so their I/O isn't double-counted on top of their parent
+/// disk's own counters (relevant when `-p` and `--total` are combined).
+fn aggregate_disk_stats(disk: &HashMap<String, DiskStats>, dm_slaves: &HashSet<String>) -> DiskStats {
+    disk.iter()
+        .filter(|(name, _)| {
+            !name.starts_with("loop") && !dm_slaves.contains(name.as_str()) && !is_partition(name)
+        })
+        .fold(DiskStats::default(), |acc, (_, stats)| acc.merged(stats))
+}
+
+/// Splits a device name into `(parent, partition suffix)` if it names a
+/// partition, so `is_partition` and `partition_parent` agree on exactly
+/// the same set of names instead of drifting apart as two rules.
+fn partition_split(name: &str) -> Option<(String, String)> {
     // NVMe partitions: nvme0n1p1, nvme0n1p2
-    if name.contains("nvme") && name.contains('p') {
-        let parts: Vec<&str> = name.split('p').collect();
-        if parts.len() > 1 {
-            if let Some(last) = parts.last() {
-                return last.chars().all(|c| c.is_ascii_digit()) && !last.is_empty();
-            }
+    if name.contains("nvme") {
+        let idx = name.rfind('p')?;
+        let suffix = &name[idx + 1..];
+        if suffix.is_empty() || !suffix.chars().all(|c| c.is_ascii_digit()) {
+            return None;
         }
+        return Some((name[..idx].to_string(), suffix.to_string()));
     }
-    // SCSI/SATA partitions: sda1, sdb2
+    // Loop devices with partitions: loop0p1, loop0p2
+    if let Some(rest) = name.strip_prefix("loop") {
+        let num_len = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        if num_len == 0 {
+            return None;
+        }
+        let suffix = rest[num_len..].strip_prefix('p')?;
+        if suffix.is_empty() || !suffix.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        return Some((name[..4 + num_len].to_string(), suffix.to_string()));
+    }
+    // SCSI/SATA partitions: sda1, sdb2, sdaa1 (once device letters roll past z)
     if name.starts_with("sd") || name.starts_with("hd") || name.starts_with("vd") {
-        let suffix: String = name.chars().skip(3).collect();
-        return suffix.chars().all(|c| c.is_ascii_digit()) && !suffix.is_empty();
+        let digit_len = name.chars().rev().take_while(|c| c.is_ascii_digit()).count();
+        let split_at = name.len() - digit_len;
+        // split_at > 2 requires at least one non-digit disk-id character
+        // after the two-letter prefix, so the bare disk (e.g. "sda") itself
+        // never parses as a partition.
+        if digit_len > 0 && split_at > 2 {
+            return Some((name[..split_at].to_string(), name[split_at..].to_string()));
+        }
+        return None;
+    }
+    None
+}
+
+fn is_partition(name: &str) -> bool {
+    partition_split(name).is_some()
+}
+
+/// Returns the parent disk of a partition, e.g. `sda1` -> `sda`,
+/// `sdaa1` -> `sdaa`, `nvme0n1p1` -> `nvme0n1`, so `-p` can indent it
+/// underneath.
+fn partition_parent(name: &str) -> Option<String> {
+    partition_split(name).map(|(parent, _)| parent)
+}
+
+const SYS_BLOCK_PATH: &str = "/sys/block";
+
+#[derive(Debug, Clone, Default)]
+struct SysBlockInfo {
+    rotational: Option<bool>,
+    model: Option<String>,
+    #[allow(dead_code)]
+    size_sectors: Option<u64>,
+}
+
+impl SysBlockInfo {
+    fn is_ssd(&self) -> Option<bool> {
+        self.rotational.map(|rotational| !rotational)
     }
-    // Loop devices with partitions
-    if name.starts_with("loop") && name.contains('p') {
-        return true;
+}
+
+/// Reads `queue/rotational`, `device/model`, and `size` for a device out of
+/// `/sys/block/<dev>`. Any attribute that doesn't exist (virtual devices,
+/// older kernels) is left as `None` rather than failing the whole lookup.
+fn read_sys_block_info(name: &str) -> SysBlockInfo {
+    read_sys_block_info_at(SYS_BLOCK_PATH, name)
+}
+
+/// Same as `read_sys_block_info`, but reading from `sys_block_path` instead
+/// of the real `/sys/block` so tests can point it at a fixture directory.
+fn read_sys_block_info_at(sys_block_path: &str, name: &str) -> SysBlockInfo {
+    let base = format!("{}/{}", sys_block_path, name);
+
+    let rotational = fs::read_to_string(format!("{}/queue/rotational", base))
+        .ok()
+        .and_then(|s| s.trim().parse::<u8>().ok())
+        .map(|v| v != 0);
+
+    let model = fs::read_to_string(format!("{}/device/model", base))
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+
+    let size_sectors = fs::read_to_string(format!("{}/size", base))
+        .ok()
+        .and_then(|s| s.trim().parse().ok());
+
+    SysBlockInfo {
+        rotational,
+        model,
+        size_sectors,
     }
-    false
 }
 
 fn print_cpu_header() {
@@ -208,19 +571,22 @@ fn print_cpu_header() {
     );
 }
 
-fn print_cpu_stats(delta: &CpuStats) {
-    let (user, sys, iowait, steal, idle, irq) = delta.percentages();
+fn print_cpu_stats(report: &CpuReport) {
     println!(
         "{:>6.2} {:>6.2} {:>6.2} {:>6.2} {:>6.2} {:>6.2}",
-        user, sys, iowait, steal, idle, irq
+        report.user, report.system, report.iowait, report.steal, report.idle, report.irq
     );
 }
 
-fn print_device_header(extended: bool) {
+fn print_device_header(extended: bool, show_model: bool) {
+    if show_model {
+        print!("{:<20} ", "Model");
+    }
     if extended {
         println!(
-            "{:<12} {:>8} {:>8} {:>10} {:>10} {:>8} {:>8} {:>7} {:>7} {:>6}",
-            "Device", "r/s", "w/s", "rkB/s", "wkB/s", "rrqm/s", "wrqm/s", "await", "svctm", "%util"
+            "{:<12} {:>8} {:>8} {:>10} {:>10} {:>8} {:>8} {:>8} {:>8} {:>7} {:>8} {:>8} {:>6} {:>8} {:>10} {:>8} {:>7} {:>7}",
+            "Device", "r/s", "w/s", "rkB/s", "wkB/s", "rrqm/s", "wrqm/s", "r_await", "w_await",
+            "svctm", "aqu-sz", "areq-sz", "%util", "d/s", "dkB/s", "drqm/s", "f/s", "f_await"
         );
     } else {
         println!(
@@ -230,88 +596,184 @@ fn print_device_header(extended: bool) {
     }
 }
 
-fn print_device_stats(
-    name: &str,
-    delta: &DiskStats,
-    interval_secs: f64,
-    extended: bool,
-    unit_divisor: f64,
-) {
-    let reads_per_sec = delta.reads_completed as f64 / interval_secs;
-    let writes_per_sec = delta.writes_completed as f64 / interval_secs;
-    let tps = reads_per_sec + writes_per_sec;
-
-    // Sectors are 512 bytes
-    let kb_read_per_sec = (delta.sectors_read as f64 * 512.0) / 1024.0 / interval_secs / unit_divisor;
-    let kb_written_per_sec = (delta.sectors_written as f64 * 512.0) / 1024.0 / interval_secs / unit_divisor;
-
-    if extended {
-        let rrqm_per_sec = delta.reads_merged as f64 / interval_secs;
-        let wrqm_per_sec = delta.writes_merged as f64 / interval_secs;
-
-        let total_ios = delta.reads_completed + delta.writes_completed;
-        let await_ms = if total_ios > 0 {
-            (delta.read_time_ms + delta.write_time_ms) as f64 / total_ios as f64
-        } else {
-            0.0
-        };
-
-        let svctm = if total_ios > 0 {
-            delta.io_time_ms as f64 / total_ios as f64
-        } else {
-            0.0
+fn print_device_stats(report: &DeviceReport, extended: bool, show_model: bool) {
+    if show_model {
+        let model = match (&report.model, report.is_ssd) {
+            (Some(model), Some(true)) => format!("{} (SSD)", model),
+            (Some(model), Some(false)) => format!("{} (HDD)", model),
+            (Some(model), None) => model.clone(),
+            (None, _) => "-".to_string(),
         };
+        print!("{:<20} ", model);
+    }
 
-        let util = (delta.io_time_ms as f64 / (interval_secs * 1000.0)) * 100.0;
-        let util = util.min(100.0);
+    // Indent partitions so they read as nested under their parent disk.
+    let device = if partition_parent(&report.device).is_some() {
+        format!("  {}", report.device)
+    } else {
+        report.device.clone()
+    };
 
+    if extended {
         println!(
-            "{:<12} {:>8.2} {:>8.2} {:>10.2} {:>10.2} {:>8.2} {:>8.2} {:>7.2} {:>7.2} {:>6.2}",
-            name, reads_per_sec, writes_per_sec, kb_read_per_sec, kb_written_per_sec,
-            rrqm_per_sec, wrqm_per_sec, await_ms, svctm, util
+            "{:<12} {:>8.2} {:>8.2} {:>10.2} {:>10.2} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>7.2} {:>8.2} {:>8.2} {:>6.2} {:>8.2} {:>10.2} {:>8.2} {:>7.2} {:>7.2}",
+            device, report.r_s, report.w_s, report.rkb_s, report.wkb_s,
+            report.rrqm_s, report.wrqm_s, report.r_await, report.w_await, report.svctm,
+            report.aqu_sz, report.areq_sz, report.util,
+            report.d_s, report.dkb_s, report.drqm_s, report.f_s, report.f_await
         );
     } else {
+        let tps = report.r_s + report.w_s;
         println!(
             "{:<12} {:>8.2} {:>10.2} {:>10.2}",
-            name, tps, kb_read_per_sec, kb_written_per_sec
+            device, tps, report.rkb_s, report.wkb_s
         );
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct Report {
+    timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avg_cpu: Option<CpuReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    disks: Option<Vec<DeviceReport>>,
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn device_reports(
+    disk: &HashMap<String, DiskStats>,
+    prev_disk: Option<&HashMap<String, DiskStats>>,
+    interval_secs: f64,
+    unit_divisor: f64,
+    total: bool,
+    dm_slaves: &HashSet<String>,
+) -> Vec<DeviceReport> {
+    if total {
+        let curr_total = aggregate_disk_stats(disk, dm_slaves);
+        let delta = match prev_disk {
+            Some(prev) => curr_total.delta(&aggregate_disk_stats(prev, dm_slaves)),
+            None => DiskStats::default(),
+        };
+        return vec![delta.report("total", interval_secs, unit_divisor, &SysBlockInfo::default())];
+    }
+
+    let mut devices: Vec<_> = disk.keys().collect();
+    devices.sort();
+
+    devices
+        .into_iter()
+        .filter_map(|name| {
+            let curr = disk.get(name)?;
+            let delta = match prev_disk.and_then(|prev| prev.get(name)) {
+                Some(prev) => curr.delta(prev),
+                None => DiskStats::default(),
+            };
+            let sys_info = read_sys_block_info(name);
+            Some(delta.report(name, interval_secs, unit_divisor, &sys_info))
+        })
+        .collect()
+}
+
+fn emit_report(args: &Args, show_cpu: bool, show_device: bool, cpu: &CpuReport, disks: &[DeviceReport]) {
+    match args.output {
+        OutputFormat::Json => {
+            let report = Report {
+                timestamp: unix_timestamp(),
+                avg_cpu: show_cpu.then(|| cpu.clone()),
+                disks: show_device.then(|| disks.to_vec()),
+            };
+            match serde_json::to_string(&report) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("failed to serialize report: {}", e),
+            }
+        }
+        OutputFormat::Plain => {
+            if show_cpu {
+                println!("avg-cpu:");
+                print_cpu_header();
+                print_cpu_stats(cpu);
+                println!();
+            }
+
+            if show_device {
+                println!("Device:");
+                print_device_header(args.extended, args.show_model);
+                for device in disks {
+                    print_device_stats(device, args.extended, args.show_model);
+                }
+                println!();
+            }
+        }
+    }
+}
+
+/// Samples CPU and disk counters on each `sample()` call, keeping the
+/// previous snapshot so every call reports the interval since the last one.
+/// It is decoupled from the renderer so `main`'s printing loop and a future
+/// embedder could share it without re-reading `/proc` state by hand. It
+/// starts no thread of its own: the caller decides when to sample.
+struct StatsCollector {
+    devices: Vec<String>,
+    partitions: bool,
+    prev_cpu: CpuStats,
+    prev_disk: HashMap<String, DiskStats>,
+    dm_slaves: HashSet<String>,
+}
+
+impl StatsCollector {
+    fn new(devices: Vec<String>, partitions: bool) -> io::Result<Self> {
+        let (prev_disk, dm_slaves) = read_disk_stats(&devices, partitions)?;
+        Ok(Self {
+            prev_cpu: read_cpu_stats()?,
+            prev_disk,
+            dm_slaves,
+            devices,
+            partitions,
+        })
+    }
+
+    /// Reads the current counters and returns the CPU and device reports for
+    /// the interval elapsed since the previous sample.
+    fn sample(&mut self, interval_secs: f64, unit_divisor: f64, total: bool) -> io::Result<(CpuReport, Vec<DeviceReport>)> {
+        let curr_cpu = read_cpu_stats()?;
+        let (curr_disk, dm_slaves) = read_disk_stats(&self.devices, self.partitions)?;
+
+        let cpu_report = curr_cpu.delta(&self.prev_cpu).report();
+        let disks = device_reports(&curr_disk, Some(&self.prev_disk), interval_secs, unit_divisor, total, &dm_slaves);
+
+        self.prev_cpu = curr_cpu;
+        self.prev_disk = curr_disk;
+        self.dm_slaves = dm_slaves;
+
+        Ok((cpu_report, disks))
+    }
+}
+
 fn main() -> io::Result<()> {
     let args = Args::parse();
 
     // Determine what to show
-    let show_cpu = args.cpu || (!args.cpu && !args.device);
-    let show_device = args.device || (!args.cpu && !args.device);
+    let show_cpu = args.cpu || !args.device;
+    let show_device = args.device || !args.cpu;
 
     let unit_divisor = if args.megabytes { 1024.0 } else { 1.0 };
 
     let interval = Duration::from_secs_f64(args.interval);
     let mut count = if args.count == 0 { u32::MAX } else { args.count };
 
-    let mut prev_cpu = read_cpu_stats()?;
-    let mut prev_disk = read_disk_stats()?;
+    let mut collector = StatsCollector::new(args.devices.clone(), args.partitions)?;
 
     // First report (since boot) unless -y
     if !args.omit_first {
-        if show_cpu {
-            println!("avg-cpu:");
-            print_cpu_header();
-            print_cpu_stats(&prev_cpu);
-            println!();
-        }
-
-        if show_device {
-            println!("Device:");
-            print_device_header(args.extended);
-            let mut devices: Vec<_> = prev_disk.keys().collect();
-            devices.sort();
-            for name in devices {
-                print_device_stats(name, &DiskStats::default(), 1.0, args.extended, unit_divisor);
-            }
-            println!();
-        }
+        let disks = device_reports(&collector.prev_disk, None, 1.0, unit_divisor, args.total, &collector.dm_slaves);
+        emit_report(&args, show_cpu, show_device, &collector.prev_cpu.report(), &disks);
 
         count = count.saturating_sub(1);
         if count == 0 {
@@ -324,33 +786,8 @@ fn main() -> io::Result<()> {
         thread::sleep(interval);
         io::stdout().flush()?;
 
-        let curr_cpu = read_cpu_stats()?;
-        let curr_disk = read_disk_stats()?;
-
-        if show_cpu {
-            println!("avg-cpu:");
-            print_cpu_header();
-            let delta = curr_cpu.delta(&prev_cpu);
-            print_cpu_stats(&delta);
-            println!();
-        }
-
-        if show_device {
-            println!("Device:");
-            print_device_header(args.extended);
-            let mut devices: Vec<_> = curr_disk.keys().collect();
-            devices.sort();
-            for name in devices {
-                if let (Some(curr), Some(prev)) = (curr_disk.get(name), prev_disk.get(name)) {
-                    let delta = curr.delta(prev);
-                    print_device_stats(name, &delta, args.interval, args.extended, unit_divisor);
-                }
-            }
-            println!();
-        }
-
-        prev_cpu = curr_cpu;
-        prev_disk = curr_disk;
+        let (cpu, disks) = collector.sample(args.interval, unit_divisor, args.total)?;
+        emit_report(&args, show_cpu, show_device, &cpu, &disks);
 
         count = count.saturating_sub(1);
         if count == 0 {
@@ -360,3 +797,348 @@ fn main() -> io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod report_json_tests {
+    use super::*;
+
+    fn sample_cpu() -> CpuReport {
+        CpuReport {
+            user: 1.0,
+            system: 2.0,
+            iowait: 3.0,
+            steal: 4.0,
+            idle: 5.0,
+            irq: 6.0,
+        }
+    }
+
+    fn sample_disk() -> DeviceReport {
+        DiskStats::default().report("sda", 1.0, 1.0, &SysBlockInfo::default())
+    }
+
+    #[test]
+    fn omits_avg_cpu_and_disks_when_their_flags_are_off() {
+        let report = Report {
+            timestamp: 0,
+            avg_cpu: None,
+            disks: None,
+        };
+
+        let json = serde_json::to_value(&report).unwrap();
+
+        assert!(json.get("avg_cpu").is_none());
+        assert!(json.get("disks").is_none());
+    }
+
+    #[test]
+    fn includes_avg_cpu_and_disks_when_present() {
+        let report = Report {
+            timestamp: 42,
+            avg_cpu: Some(sample_cpu()),
+            disks: Some(vec![sample_disk()]),
+        };
+
+        let json = serde_json::to_value(&report).unwrap();
+
+        assert_eq!(json["timestamp"], 42);
+        assert!(json["avg_cpu"]["user"].is_number());
+        assert_eq!(json["disks"].as_array().unwrap().len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod parse_disk_stats_tests {
+    use super::*;
+
+    #[test]
+    fn older_kernel_line_defaults_discard_and_flush_fields_to_zero() {
+        let content = "   8       0 sda 100 5 2000 150 50 2 400 20 0 40 15";
+
+        let stats = parse_disk_stats(content);
+
+        let sda = stats.get("sda").expect("sda should be parsed");
+        assert_eq!(sda.reads_completed, 100);
+        assert_eq!(sda.weighted_io_time_ms, 15);
+        assert_eq!(sda.discards_completed, 0);
+        assert_eq!(sda.discards_merged, 0);
+        assert_eq!(sda.sectors_discarded, 0);
+        assert_eq!(sda.discard_time_ms, 0);
+        assert_eq!(sda.flushes_completed, 0);
+        assert_eq!(sda.flush_time_ms, 0);
+    }
+
+    #[test]
+    fn modern_kernel_line_parses_discard_and_flush_fields() {
+        let content = "   8       0 sda 100 5 2000 150 50 2 400 20 0 40 15 7 1 900 30 3 12";
+
+        let stats = parse_disk_stats(content);
+
+        let sda = stats.get("sda").expect("sda should be parsed");
+        assert_eq!(sda.discards_completed, 7);
+        assert_eq!(sda.discards_merged, 1);
+        assert_eq!(sda.sectors_discarded, 900);
+        assert_eq!(sda.discard_time_ms, 30);
+        assert_eq!(sda.flushes_completed, 3);
+        assert_eq!(sda.flush_time_ms, 12);
+    }
+
+    #[test]
+    fn short_lines_are_skipped() {
+        let content = "   8       0 sda 100 5 2000";
+
+        let stats = parse_disk_stats(content);
+
+        assert!(stats.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod disk_stats_report_tests {
+    use super::*;
+
+    #[test]
+    fn computes_split_await_queue_and_request_size() {
+        let stats = DiskStats {
+            reads_completed: 10,
+            read_time_ms: 500,
+            writes_completed: 5,
+            write_time_ms: 100,
+            sectors_read: 2000,
+            sectors_written: 1000,
+            weighted_io_time_ms: 2000,
+            ..Default::default()
+        };
+
+        let report = stats.report("sda", 10.0, 1.0, &SysBlockInfo::default());
+
+        assert_eq!(report.r_await, 50.0);
+        assert_eq!(report.w_await, 20.0);
+        assert_eq!(report.aqu_sz, 0.2);
+        assert_eq!(report.areq_sz, 100.0);
+    }
+
+    #[test]
+    fn await_and_areq_sz_are_zero_when_no_ios_happened() {
+        let stats = DiskStats::default();
+
+        let report = stats.report("sda", 10.0, 1.0, &SysBlockInfo::default());
+
+        assert_eq!(report.r_await, 0.0);
+        assert_eq!(report.w_await, 0.0);
+        assert_eq!(report.aqu_sz, 0.0);
+        assert_eq!(report.areq_sz, 0.0);
+    }
+}
+
+#[cfg(test)]
+mod aggregate_disk_stats_tests {
+    use super::*;
+
+    fn stats(reads_completed: u64) -> DiskStats {
+        DiskStats {
+            reads_completed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sums_across_devices_and_skips_loopback() {
+        let mut disk = HashMap::new();
+        disk.insert("sda".to_string(), stats(100));
+        disk.insert("sdb".to_string(), stats(200));
+        disk.insert("loop0".to_string(), stats(9999));
+
+        let total = aggregate_disk_stats(&disk, &HashSet::new());
+
+        assert_eq!(total.reads_completed, 300);
+    }
+
+    #[test]
+    fn excludes_dm_slaves_so_their_io_is_not_double_counted() {
+        let mut disk = HashMap::new();
+        disk.insert("sda".to_string(), stats(1000));
+        disk.insert("sdb".to_string(), stats(500));
+        // "vg0-root" is the synthesized dm row, already the sum of sda+sdb.
+        disk.insert("vg0-root".to_string(), stats(1500));
+
+        let dm_slaves: HashSet<String> = ["sda".to_string(), "sdb".to_string()].into_iter().collect();
+        let total = aggregate_disk_stats(&disk, &dm_slaves);
+
+        assert_eq!(total.reads_completed, 1500);
+    }
+
+    #[test]
+    fn excludes_partitions_so_their_io_is_not_double_counted() {
+        let mut disk = HashMap::new();
+        disk.insert("sda".to_string(), stats(1000));
+        disk.insert("sda1".to_string(), stats(400));
+        disk.insert("sda2".to_string(), stats(600));
+
+        let total = aggregate_disk_stats(&disk, &HashSet::new());
+
+        assert_eq!(total.reads_completed, 1000);
+    }
+}
+
+#[cfg(test)]
+mod sys_block_fixture {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Creates a fresh scratch directory under the OS temp dir to stand in
+    /// for `/sys/block` in tests that exercise the `_at` variants.
+    pub(super) fn unique_dir(tag: &str) -> PathBuf {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("iostat-test-{}-{}-{}", std::process::id(), tag, n));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
+
+#[cfg(test)]
+mod device_filter_tests {
+    use super::*;
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        assert!(matches_device_filter("sda", &[]));
+        assert!(matches_device_filter("nvme0n1", &[]));
+    }
+
+    #[test]
+    fn matches_exact_device_and_its_partitions() {
+        let devices = vec!["sda".to_string()];
+        assert!(matches_device_filter("sda", &devices));
+        assert!(matches_device_filter("sda1", &devices));
+        assert!(!matches_device_filter("sdb", &devices));
+    }
+
+    #[test]
+    fn does_not_match_unrelated_devices_sharing_a_prefix() {
+        let devices = vec!["sda".to_string()];
+        assert!(!matches_device_filter("sdaa", &devices));
+        assert!(!matches_device_filter("sdaa1", &devices));
+
+        let devices = vec!["nvme0n1".to_string()];
+        assert!(!matches_device_filter("nvme0n10", &devices));
+        assert!(matches_device_filter("nvme0n1p1", &devices));
+    }
+}
+
+#[cfg(test)]
+mod read_sys_block_info_tests {
+    use super::*;
+    use sys_block_fixture::unique_dir;
+
+    #[test]
+    fn reads_rotational_and_model() {
+        let sys_block = unique_dir("sysinfo");
+        let dev = sys_block.join("sda");
+        fs::create_dir_all(dev.join("queue")).unwrap();
+        fs::create_dir_all(dev.join("device")).unwrap();
+        fs::write(dev.join("queue/rotational"), "0\n").unwrap();
+        fs::write(dev.join("device/model"), "Samsung SSD 970\n").unwrap();
+
+        let info = read_sys_block_info_at(sys_block.to_str().unwrap(), "sda");
+
+        assert_eq!(info.is_ssd(), Some(true));
+        assert_eq!(info.model.as_deref(), Some("Samsung SSD 970"));
+    }
+
+    #[test]
+    fn missing_attributes_are_none_rather_than_an_error() {
+        let sys_block = unique_dir("sysinfo-missing");
+
+        let info = read_sys_block_info_at(sys_block.to_str().unwrap(), "sda");
+
+        assert_eq!(info.rotational, None);
+        assert_eq!(info.model, None);
+        assert_eq!(info.is_ssd(), None);
+    }
+}
+
+#[cfg(test)]
+mod partition_tests {
+    use super::*;
+
+    #[test]
+    fn scsi_sata_and_virtio_partitions() {
+        assert_eq!(partition_parent("sda1"), Some("sda".to_string()));
+        assert_eq!(partition_parent("hdb2"), Some("hdb".to_string()));
+        assert_eq!(partition_parent("vdc10"), Some("vdc".to_string()));
+        assert_eq!(partition_parent("sda"), None);
+    }
+
+    #[test]
+    fn scsi_disk_ids_past_z_keep_their_full_letter_prefix() {
+        assert!(is_partition("sdaa1"));
+        assert_eq!(partition_parent("sdaa1"), Some("sdaa".to_string()));
+    }
+
+    #[test]
+    fn nvme_partitions() {
+        assert_eq!(partition_parent("nvme0n1p1"), Some("nvme0n1".to_string()));
+        assert_eq!(partition_parent("nvme0n1"), None);
+    }
+
+    #[test]
+    fn loop_partitions_are_recognized_and_indented() {
+        assert!(is_partition("loop0p1"));
+        assert_eq!(partition_parent("loop0p1"), Some("loop0".to_string()));
+        assert_eq!(partition_parent("loop0"), None);
+    }
+}
+
+#[cfg(test)]
+mod resolve_device_mapper_tests {
+    use super::*;
+    use sys_block_fixture::unique_dir;
+
+    fn stats(reads_completed: u64) -> DiskStats {
+        DiskStats {
+            reads_completed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sums_slave_devices_under_the_friendly_dm_name() {
+        let sys_block = unique_dir("dm-slaves");
+        let dm = sys_block.join("dm-0");
+        fs::create_dir_all(dm.join("dm")).unwrap();
+        fs::create_dir_all(dm.join("slaves")).unwrap();
+        fs::write(dm.join("dm/name"), "vg0-root\n").unwrap();
+        fs::File::create(dm.join("slaves/sda")).unwrap();
+        fs::File::create(dm.join("slaves/sdb")).unwrap();
+
+        let mut raw = HashMap::new();
+        raw.insert("dm-0".to_string(), stats(0));
+        raw.insert("sda".to_string(), stats(1000));
+        raw.insert("sdb".to_string(), stats(500));
+
+        let (resolved, slaves) = resolve_device_mapper_at(sys_block.to_str().unwrap(), raw);
+
+        assert_eq!(resolved.get("vg0-root").unwrap().reads_completed, 1500);
+        assert!(!resolved.contains_key("dm-0"));
+        assert_eq!(slaves, ["sda".to_string(), "sdb".to_string()].into_iter().collect());
+    }
+
+    #[test]
+    fn falls_back_to_its_own_counters_when_slaves_are_unreadable() {
+        let sys_block = unique_dir("dm-no-slaves");
+        let dm = sys_block.join("dm-0");
+        fs::create_dir_all(dm.join("dm")).unwrap();
+        fs::write(dm.join("dm/name"), "vg0-root\n").unwrap();
+        // No `slaves/` directory created at all.
+
+        let mut raw = HashMap::new();
+        raw.insert("dm-0".to_string(), stats(42));
+
+        let (resolved, slaves) = resolve_device_mapper_at(sys_block.to_str().unwrap(), raw);
+
+        assert_eq!(resolved.get("vg0-root").unwrap().reads_completed, 42);
+        assert!(slaves.is_empty());
+    }
+}